@@ -1,28 +1,86 @@
+#![cfg_attr(not(feature = "std"), no_std)]
 #![warn(missing_docs)]
 
 //! HashMap with entries living for limited period of time.
 
+#[cfg(feature = "std")]
 extern crate time;
+#[cfg(not(feature = "std"))]
+extern crate alloc;
+extern crate hashbrown;
 
-use std::mem;
-use std::cmp;
-use std::hash::Hash;
+#[cfg(feature = "std")]
+use std::vec::Vec;
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+
+#[cfg(feature = "std")]
+use std::collections::BinaryHeap;
+#[cfg(not(feature = "std"))]
+use alloc::collections::BinaryHeap;
+
+#[cfg(feature = "std")]
 use std::cell::RefCell;
-use std::collections::HashMap;
-use std::collections::hash_map::Entry;
-use std::ops::{Deref, DerefMut};
+#[cfg(not(feature = "std"))]
+use core::cell::RefCell;
+
+use core::cmp;
+use core::cmp::Reverse;
+use core::hash::{Hash, BuildHasher};
+use core::mem;
+use core::ops::{Deref, DerefMut};
+use hashbrown::HashMap;
+use hashbrown::hash_map::Entry;
+
+/// Default `BuildHasher` used when none is supplied: `RandomState` under `std`,
+/// hashbrown's own default (no OS randomness required) under `alloc`-only builds.
+#[cfg(feature = "std")]
+type DefaultHasher = std::collections::hash_map::RandomState;
+#[cfg(not(feature = "std"))]
+type DefaultHasher = hashbrown::hash_map::DefaultHashBuilder;
 
 type LifetimeSec = u32;
 
+/// Entry in `expiry_heap`, ordered purely by `expiry` so `K` never needs to be
+/// `Ord` to support the heap's `push`/`pop` — only `Eq + Hash + Clone`, same as
+/// everywhere else in the public API.
+struct HeapEntry<K> {
+	expiry: i64,
+	key: K
+}
+
+impl<K> PartialEq for HeapEntry<K> {
+	fn eq(&self, other: &Self) -> bool {
+		self.expiry == other.expiry
+	}
+}
+impl<K> Eq for HeapEntry<K> {}
+
+impl<K> PartialOrd for HeapEntry<K> {
+	fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
+		Some(self.cmp(other))
+	}
+}
+impl<K> Ord for HeapEntry<K> {
+	fn cmp(&self, other: &Self) -> cmp::Ordering {
+		self.expiry.cmp(&other.expiry)
+	}
+}
+
 /// Time provider.
+///
+/// Under `std` this crate ships `StandardTimer`; `alloc`-only users provide
+/// their own implementation driven by whatever clock is available to them.
 pub trait Timer {
 	/// Returns current timestamp in seconds.
 	fn get_time(&self) -> i64;
 }
 
 /// Standard time provider returning current time.
+#[cfg(feature = "std")]
 #[derive(Default)]
 pub struct StandardTimer;
+#[cfg(feature = "std")]
 impl Timer for StandardTimer {
 	fn get_time(&self) -> i64 {
 		time::get_time().sec
@@ -34,49 +92,313 @@ impl Timer for StandardTimer {
 ///
 /// Pruning does not occur automatically, make sure to call `prune` method
 /// to remove old entries.
-pub struct TransientHashMap<K, V, T = StandardTimer> where T: Timer {
-	backing: HashMap<K, V>,
-	timestamps: RefCell<HashMap<K, i64>>,
+///
+/// `T` defaults to `StandardTimer` only under `std`: that default provider
+/// needs a clock, which isn't available to `alloc`-only builds, so those
+/// builds must always spell out their own `Timer`.
+#[cfg(feature = "std")]
+pub struct TransientHashMap<K, V, T = StandardTimer, S = DefaultHasher> where T: Timer, S: BuildHasher {
+	backing: HashMap<K, V, S>,
+	timestamps: RefCell<HashMap<K, i64, S>>,
+	// Min-heap of expiry entries used to find expired entries without scanning
+	// the whole map. Entries are pushed lazily on every access, so a key may
+	// appear several times; only the one matching the current `timestamps`
+	// entry is authoritative, the rest are stale and discarded when popped.
+	expiry_heap: RefCell<BinaryHeap<Reverse<HeapEntry<K>>>>,
+	per_key_lifetime: HashMap<K, LifetimeSec, S>,
+	// Weight recorded for each key that was actually weighed, i.e. inserted via
+	// `insert`/`insert_with_lifetime`. Keys added through `entry` have no entry
+	// here, so their (unknown) weight is never subtracted from `current_weight`
+	// when they are later removed.
+	key_weight: HashMap<K, u64, S>,
+	capacity: Option<usize>,
+	max_weight: Option<u64>,
+	current_weight: u64,
+	weight_fn: Option<fn(&V) -> u64>,
+	// Entries evicted by the most recent `insert`/`insert_with_lifetime`/`entry`
+	// call, kept here rather than returned so `insert`'s signature stays
+	// `Option<V>` for callers who never opt into `capacity`/`max_weight`.
+	last_evicted: Vec<(K, V)>,
 	lifetime: LifetimeSec,
 	timer: T
 }
+/// `HashMap` with entries that will be garbage collected (pruned)
+/// after not being used for specified time.
+///
+/// Pruning does not occur automatically, make sure to call `prune` method
+/// to remove old entries.
+///
+/// `alloc`-only builds have no `StandardTimer` to default `T` to, so `T`
+/// must always be spelled out explicitly here (see the `std` variant above).
+#[cfg(not(feature = "std"))]
+pub struct TransientHashMap<K, V, T, S = DefaultHasher> where T: Timer, S: BuildHasher {
+	backing: HashMap<K, V, S>,
+	timestamps: RefCell<HashMap<K, i64, S>>,
+	expiry_heap: RefCell<BinaryHeap<Reverse<HeapEntry<K>>>>,
+	per_key_lifetime: HashMap<K, LifetimeSec, S>,
+	key_weight: HashMap<K, u64, S>,
+	capacity: Option<usize>,
+	max_weight: Option<u64>,
+	current_weight: u64,
+	weight_fn: Option<fn(&V) -> u64>,
+	last_evicted: Vec<(K, V)>,
+	lifetime: LifetimeSec,
+	timer: T
+}
+
+/// A value whose size should count against a `TransientHashMap`'s `max_weight`,
+/// set via `with_max_weight`.
+pub trait Weight {
+	/// Returns this value's weight.
+	fn weight(&self) -> u64;
+}
 
+#[cfg(feature = "std")]
 impl<K, V> TransientHashMap<K, V, StandardTimer> where K: Eq + Hash + Clone {
 	/// Creates new `TransientHashMap` with standard timer and specified entries lifetime.
 	pub fn new(lifetime: LifetimeSec) -> Self {
 		TransientHashMap::new_with_timer(lifetime, Default::default())
 	}
+
+	/// Creates new `TransientHashMap` with standard timer, specified entries lifetime
+	/// and a maximum number of entries, evicting the least recently used one once full.
+	pub fn with_capacity(lifetime: LifetimeSec, capacity: usize) -> Self {
+		TransientHashMap::with_capacity_and_timer(lifetime, capacity, Default::default())
+	}
+
+	/// Creates new `TransientHashMap` with standard timer, specified entries lifetime
+	/// and a maximum total weight, evicting least recently used entries once `V::weight`
+	/// would push the map over `max_weight`.
+	pub fn with_max_weight(lifetime: LifetimeSec, max_weight: u64) -> Self where V: Weight {
+		TransientHashMap::with_max_weight_and_timer(lifetime, max_weight, Default::default())
+	}
 }
 
-impl<K, V, T> TransientHashMap<K, V, T> where K: Eq + Hash + Clone, T: Timer {
+impl<K, V, T> TransientHashMap<K, V, T, DefaultHasher> where K: Eq + Hash + Clone, T: Timer {
 	/// Creates new `TransientHashMap` with given timer and specfied entries lifetime.
+	///
+	/// Fixed to the default hasher; use `new_with_hasher` to pick your own `BuildHasher`.
 	pub fn new_with_timer(lifetime: LifetimeSec, t: T) -> Self {
 		TransientHashMap {
-			backing: HashMap::new(),
-			timestamps: RefCell::new(HashMap::new()),
+			backing: HashMap::default(),
+			timestamps: RefCell::new(HashMap::default()),
+			expiry_heap: RefCell::new(BinaryHeap::new()),
+			per_key_lifetime: HashMap::default(),
+			key_weight: HashMap::default(),
+			capacity: None,
+			max_weight: None,
+			current_weight: 0,
+			weight_fn: None,
+			last_evicted: Vec::new(),
 			lifetime: lifetime,
 			timer: t
 		}
 	}
 
+	/// Creates new `TransientHashMap` with given timer, entries lifetime and a maximum
+	/// number of entries. Once full, inserting a key that is not already present
+	/// evicts the least recently used entry to make room.
+	pub fn with_capacity_and_timer(lifetime: LifetimeSec, capacity: usize, t: T) -> Self {
+		let mut map = TransientHashMap::new_with_timer(lifetime, t);
+		map.capacity = Some(capacity);
+		map
+	}
+
+	/// Creates new `TransientHashMap` with given timer, entries lifetime and a maximum
+	/// total weight. Once inserting `value` would push `current_weight` over
+	/// `max_weight`, least recently used entries are evicted to make room.
+	pub fn with_max_weight_and_timer(lifetime: LifetimeSec, max_weight: u64, t: T) -> Self where V: Weight {
+		let mut map = TransientHashMap::new_with_timer(lifetime, t);
+		map.max_weight = Some(max_weight);
+		map.weight_fn = Some(V::weight);
+		map
+	}
+}
+
+impl<K, V, T, S> TransientHashMap<K, V, T, S> where K: Eq + Hash + Clone, T: Timer, S: BuildHasher + Clone {
+	/// Creates new `TransientHashMap` with the given timer and `BuildHasher`.
+	///
+	/// `alloc`-only builds (no `std` feature) have no `RandomState`; through
+	/// `new_with_timer` they get hashbrown's default hasher, and here they can
+	/// bring their own instead, e.g. `ahash`, alongside their own `Timer`.
+	pub fn new_with_hasher(lifetime: LifetimeSec, t: T, hash_builder: S) -> Self {
+		TransientHashMap {
+			backing: HashMap::with_hasher(hash_builder.clone()),
+			timestamps: RefCell::new(HashMap::with_hasher(hash_builder.clone())),
+			expiry_heap: RefCell::new(BinaryHeap::new()),
+			per_key_lifetime: HashMap::with_hasher(hash_builder.clone()),
+			key_weight: HashMap::with_hasher(hash_builder),
+			capacity: None,
+			max_weight: None,
+			current_weight: 0,
+			weight_fn: None,
+			last_evicted: Vec::new(),
+			lifetime: lifetime,
+			timer: t
+		}
+	}
+
+	/// Creates new `TransientHashMap` with the given timer, `BuildHasher`, and a maximum
+	/// number of entries, evicting the least recently used one once full.
+	pub fn with_capacity_and_hasher(lifetime: LifetimeSec, capacity: usize, t: T, hash_builder: S) -> Self {
+		let mut map = TransientHashMap::new_with_hasher(lifetime, t, hash_builder);
+		map.capacity = Some(capacity);
+		map
+	}
+
+	/// Creates new `TransientHashMap` with the given timer, `BuildHasher`, and a maximum
+	/// total weight, evicting least recently used entries once `V::weight` would push
+	/// the map over `max_weight`.
+	pub fn with_max_weight_and_hasher(lifetime: LifetimeSec, max_weight: u64, t: T, hash_builder: S) -> Self where V: Weight {
+		let mut map = TransientHashMap::new_with_hasher(lifetime, t, hash_builder);
+		map.max_weight = Some(max_weight);
+		map.weight_fn = Some(V::weight);
+		map
+	}
+}
+
+impl<K, V, T, S> TransientHashMap<K, V, T, S> where K: Eq + Hash + Clone, T: Timer, S: BuildHasher {
+	/// Returns the maximum number of entries this map will hold, or `None` if unbounded.
+	pub fn capacity(&self) -> Option<usize> {
+		self.capacity
+	}
+
+	/// Returns the maximum total weight this map will hold, or `None` if unbounded.
+	pub fn max_weight(&self) -> Option<u64> {
+		self.max_weight
+	}
+
+	/// Returns the sum of `Weight::weight` over the entries still held that were
+	/// weighed by `insert`/`insert_with_lifetime`.
+	///
+	/// Values added through `entry` or the backing `HashMap` are not counted, and
+	/// removing an entry through the backing `HashMap` does not subtract its weight.
+	pub fn current_weight(&self) -> u64 {
+		self.current_weight
+	}
+
 	/// Insert new entry to this map overwriting any previous entry.
 	///
-	/// Prolongs lifetime of `key`.
+	/// Prolongs lifetime of `key`. If the map is at capacity or would exceed
+	/// `max_weight`, least recently used entries are evicted first; fetch them
+	/// with `take_evicted` if you need to react to what was dropped.
 	pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+		let incoming_weight = self.weight_fn.map(|weight| weight(&value));
+		self.evict_if_full(&key, incoming_weight.unwrap_or(0));
+		self.note_used_if(true, &key);
+		self.track_weight(&key, incoming_weight);
+		self.backing.insert(key, value)
+	}
+
+	/// Insert new entry with its own lifetime, overriding the map's default for this `key`.
+	///
+	/// Prolongs lifetime of `key` using `lifetime` instead of the map-wide default,
+	/// letting short-lived and long-lived entries coexist in the same map. Capacity
+	/// and weight eviction behave as in `insert`.
+	pub fn insert_with_lifetime(&mut self, key: K, value: V, lifetime: LifetimeSec) -> Option<V> {
+		let incoming_weight = self.weight_fn.map(|weight| weight(&value));
+		self.evict_if_full(&key, incoming_weight.unwrap_or(0));
+		self.per_key_lifetime.insert(key.clone(), lifetime);
 		self.note_used_if(true, &key);
+		self.track_weight(&key, incoming_weight);
 		self.backing.insert(key, value)
 	}
 
 	/// Insert new entry to this map overwriting any previous entry.
 	///
-	/// Always prolongs the lifetime of `key`.
+	/// Always prolongs the lifetime of `key`. If the map is at capacity and `key`
+	/// is not already present, the least recently used entry is evicted to make room,
+	/// *even if the returned `Entry` is only inspected or `and_modify`d and nothing
+	/// ends up being inserted* — eviction happens unconditionally on every call, not
+	/// only when an insertion follows.
 	/// TODO [ToDr] Should only prolong if new item is inserted or entry is occupied.
-	pub fn entry(&mut self, key: K) -> Entry<K, V> {
+	/// TODO [ToDr] Should only evict for capacity if new item is actually inserted.
+	/// Note: values inserted through the returned `Entry` are not weighed, since
+	/// their weight is not known until the caller decides what to do with it.
+	pub fn entry(&mut self, key: K) -> Entry<K, V, S> {
 		// TODO [ToDr] note used only if occupied or inserted!
+		self.evict_if_full(&key, 0);
 		self.note_used_if(true, &key);
 		self.backing.entry(key)
 	}
 
+	/// Returns the entries evicted by the most recent `insert`/`insert_with_lifetime`/
+	/// `entry` call, leaving an empty list behind.
+	///
+	/// Kept separate from `insert`'s return value so callers who never opt into
+	/// `capacity`/`max_weight` keep the plain `Option<V>` they've always gotten back.
+	pub fn take_evicted(&mut self) -> Vec<(K, V)> {
+		mem::take(&mut self.last_evicted)
+	}
+
+	/// Evicts least recently used entries as needed to keep `key`'s insertion
+	/// within both `capacity` and `max_weight`, recording everything evicted
+	/// into `last_evicted`.
+	///
+	/// `key` itself is never picked as a victim: when `key` is already present,
+	/// it is about to be replaced rather than added, so it must not count
+	/// towards "is the map full" on either axis.
+	fn evict_if_full(&mut self, key: &K, incoming_weight: u64) {
+		self.last_evicted.clear();
+
+		if let Some(capacity) = self.capacity {
+			if !self.backing.contains_key(key) && self.backing.len() >= capacity {
+				if let Some(victim) = self.evict_lru(key) {
+					self.last_evicted.push(victim);
+				}
+			}
+		}
+
+		if let Some(max_weight) = self.max_weight {
+			let existing_weight = self.key_weight.get(key).cloned().unwrap_or(0);
+			while self.current_weight - existing_weight + incoming_weight > max_weight {
+				match self.evict_lru(key) {
+					Some(victim) => self.last_evicted.push(victim),
+					None => break
+				}
+			}
+		}
+	}
+
+	/// Removes and returns the least recently used entry other than `exclude`,
+	/// picked from the recorded access timestamps.
+	fn evict_lru(&mut self, exclude: &K) -> Option<(K, V)> {
+		let victim = self.timestamps.borrow().iter()
+			.filter(|&(key, _)| key != exclude)
+			.min_by_key(|&(_, time)| *time)
+			.map(|(key, _)| key.clone());
+
+		victim.and_then(|key| {
+			self.timestamps.borrow_mut().remove(&key);
+			self.per_key_lifetime.remove(&key);
+			self.untrack_weight(&key);
+			self.backing.remove(&key).map(|value| (key, value))
+		})
+	}
+
+	/// Records `incoming_weight` as `key`'s contribution to `current_weight`,
+	/// first undoing its previous contribution (if any) so a replacing `insert`
+	/// never double-counts the old value's share. A `None` weight (no weight
+	/// function configured) is a no-op.
+	#[inline]
+	fn track_weight(&mut self, key: &K, incoming_weight: Option<u64>) {
+		if let Some(incoming_weight) = incoming_weight {
+			self.untrack_weight(key);
+			self.key_weight.insert(key.clone(), incoming_weight);
+			self.current_weight += incoming_weight;
+		}
+	}
+
+	/// Undoes `key`'s recorded contribution to `current_weight`, if it has one.
+	/// Keys that were only ever touched through `entry` have no recorded weight
+	/// and are left untouched, so removing them can never underflow `current_weight`.
+	#[inline]
+	fn untrack_weight(&mut self, key: &K) {
+		if let Some(weight) = self.key_weight.remove(key) {
+			self.current_weight -= weight;
+		}
+	}
+
 	/// Gets reference to stored value.
 	///
 	/// Prolongs lifetime of `key` if is in the map.
@@ -109,55 +431,163 @@ impl<K, V, T> TransientHashMap<K, V, T> where K: Eq + Hash + Clone, T: Timer {
 		let timestamps = self.timestamps.borrow();
 		timestamps.get(key).map(|time| {
 				let time = self.timer.get_time() - time;
-				cmp::max(0, self.lifetime as i64 - time) as LifetimeSec
+				cmp::max(0, self.lifetime_of(key) as i64 - time) as LifetimeSec
 		})
 	}
 
+	/// Returns the lifetime that applies to `key`: its per-key override if one
+	/// was set via `insert_with_lifetime`, otherwise the map-wide default.
+	#[inline]
+	fn lifetime_of(&self, key: &K) -> LifetimeSec {
+		self.per_key_lifetime.get(key).cloned().unwrap_or(self.lifetime)
+	}
+
 	#[inline]
 	fn note_used_if(&self, condition: bool, key: &K) {
 		if condition {
-			self.timestamps.borrow_mut().insert(key.clone(), self.timer.get_time());
+			let now = self.timer.get_time();
+			self.timestamps.borrow_mut().insert(key.clone(), now);
+			self.expiry_heap.borrow_mut().push(Reverse(HeapEntry { expiry: now + self.lifetime_of(key) as i64, key: key.clone() }));
 		}
 	}
 
-	/// Clear overdue entries from the `TransientHashMap`.
+	/// Clear overdue entries from the `TransientHashMap`, returning their keys.
 	pub fn prune(&mut self) -> Vec<K> {
+		self.drain_expired().map(|(key, _)| key).collect()
+	}
+
+	/// Retains only the entries for which `f` returns `true`, in a single pass
+	/// that also garbage-collects expired entries.
+	///
+	/// Expired entries are dropped without ever being passed to `f`; `f` is
+	/// only called for entries that are still live.
+	pub fn retain<F: FnMut(&K, &V) -> bool>(&mut self, mut f: F) {
+		let now = self.timer.get_time();
+		let keys: Vec<K> = self.backing.keys().cloned().collect();
+
+		for key in keys {
+			let expired = match self.timestamps.borrow().get(&key) {
+				Some(&time) => now - time >= self.lifetime_of(&key) as i64,
+				None => false
+			};
+
+			let keep = !expired && self.backing.get(&key).is_some_and(|value| f(&key, value));
+
+			if !keep {
+				self.timestamps.borrow_mut().remove(&key);
+				self.per_key_lifetime.remove(&key);
+				self.untrack_weight(&key);
+				self.backing.remove(&key);
+			}
+		}
+	}
+
+	/// Returns a lazy iterator over expired entries, removing each `(key, value)`
+	/// pair from the map as it is yielded.
+	///
+	/// Unlike `prune`, nothing is allocated up front: a caller that stops
+	/// iterating early (or ignores the result) never touches unvisited entries.
+	pub fn drain_expired(&mut self) -> DrainExpired<K, V, T, S> {
+		let now = self.timer.get_time();
+		DrainExpired {
+			map: self,
+			now: now
+		}
+	}
+
+	/// Returns the remaining lifetime of the soonest-to-expire live entry,
+	/// or `None` if the map is empty.
+	pub fn next_expiry(&self) -> Option<LifetimeSec> {
 		let now = self.timer.get_time();
 
-		let timestamps = mem::replace(&mut self.timestamps, RefCell::new(HashMap::new()));
-		let (ok, removed) = timestamps.into_inner().into_iter()
-			.partition(|entry| now - entry.1 < self.lifetime as i64);
-		*self.timestamps.borrow_mut() = ok;
+		let mut heap = self.expiry_heap.borrow_mut();
+		let timestamps = self.timestamps.borrow();
 
-		removed
-			.into_iter()
-			.map(|entry| {
-				self.backing.remove(&entry.0);
-				entry.0
-			})
-			.collect()
+		loop {
+			let (expiry, key) = match heap.peek() {
+				Some(&Reverse(HeapEntry { expiry, ref key })) => (expiry, key.clone()),
+				None => return None,
+			};
+
+			if timestamps.get(&key).is_some_and(|&time| time + self.lifetime_of(&key) as i64 == expiry) {
+				return Some(cmp::max(0, expiry - now) as LifetimeSec);
+			}
+
+			// Stale entry superseded by a later use of the same key; discard it.
+			heap.pop();
+		}
 	}
 
 	/// Get a reference to backing `HashMap`.
-	pub fn direct(&self) -> &HashMap<K, V> {
+	pub fn direct(&self) -> &HashMap<K, V, S> {
 		&self.backing
 	}
 
 	/// Get the mutable reference to backing `HashMap`.
-	pub fn direct_mut(&mut self) -> &mut HashMap<K, V> {
+	pub fn direct_mut(&mut self) -> &mut HashMap<K, V, S> {
 		&mut self.backing
 	}
 }
 
-impl<K, V, T> Deref for TransientHashMap<K, V, T> where T: Timer {
-	type Target = HashMap<K, V>;
+/// Lazy iterator over expired entries, created by `TransientHashMap::drain_expired`.
+pub struct DrainExpired<'a, K: 'a, V: 'a, T: 'a, S: 'a> where K: Eq + Hash + Clone, T: Timer, S: BuildHasher {
+	map: &'a mut TransientHashMap<K, V, T, S>,
+	now: i64
+}
+
+impl<'a, K, V, T, S> Iterator for DrainExpired<'a, K, V, T, S> where K: Eq + Hash + Clone, T: Timer, S: BuildHasher {
+	type Item = (K, V);
+
+	fn next(&mut self) -> Option<(K, V)> {
+		loop {
+			let key = {
+				let mut heap = self.map.expiry_heap.borrow_mut();
+				let timestamps = self.map.timestamps.borrow();
+
+				let expiry = match heap.peek() {
+					Some(&Reverse(HeapEntry { expiry, .. })) => expiry,
+					None => return None
+				};
+				if expiry > self.now {
+					return None;
+				}
+
+				let Reverse(HeapEntry { expiry, key }) = heap.pop().expect("just peeked Some; qed");
+
+				// This heap entry is authoritative only if `timestamps` still has `key`
+				// *and* its expiry matches exactly. Anything else — no entry at all
+				// (the key was already removed by eviction or `retain`) or a later
+				// expiry (the key was used again since this entry was pushed) — means
+				// it's stale and must be skipped, never removed. A key removed through
+				// `direct_mut` still has its `timestamps` entry: that is cleared below
+				// and, having no value to yield, the key is skipped.
+				let authoritative = timestamps.get(&key).is_some_and(|&time| time + self.map.lifetime_of(&key) as i64 == expiry);
+				if !authoritative {
+					continue;
+				}
+
+				key
+			};
+
+			self.map.timestamps.borrow_mut().remove(&key);
+			self.map.per_key_lifetime.remove(&key);
+			self.map.untrack_weight(&key);
+			if let Some(value) = self.map.backing.remove(&key) {
+				return Some((key, value));
+			}
+		}
+	}
+}
+
+impl<K, V, T, S> Deref for TransientHashMap<K, V, T, S> where T: Timer, S: BuildHasher {
+	type Target = HashMap<K, V, S>;
 
 	fn deref(&self) -> &Self::Target {
 		&self.backing
 	}
 }
 
-impl<K, V, T> DerefMut for TransientHashMap<K, V, T> where T: Timer {
+impl<K, V, T, S> DerefMut for TransientHashMap<K, V, T, S> where T: Timer, S: BuildHasher {
 	fn deref_mut(&mut self) -> &mut Self::Target {
 		&mut self.backing
 	}
@@ -165,8 +595,21 @@ impl<K, V, T> DerefMut for TransientHashMap<K, V, T> where T: Timer {
 
 #[cfg(test)]
 mod test {
+	#[cfg(feature = "std")]
 	use std::cell::Cell;
-	use super::{TransientHashMap, Timer};
+	#[cfg(not(feature = "std"))]
+	use core::cell::Cell;
+	#[cfg(not(feature = "std"))]
+	use alloc::vec;
+	#[cfg(not(feature = "std"))]
+	use alloc::vec::Vec;
+	use super::{TransientHashMap, Timer, Weight};
+
+	impl Weight for &'static str {
+		fn weight(&self) -> u64 {
+			self.len() as u64
+		}
+	}
 
 	struct TestTimer<'a> {
 		time: &'a Cell<i64>
@@ -239,6 +682,218 @@ mod test {
 		assert!(keys.contains(&3));
 	}
 
+	#[test]
+	fn should_report_next_expiry() {
+		// given
+		let time = Cell::new(0);
+		let timer = TestTimer {
+			time: &time
+		};
+		let mut t_map = TransientHashMap::new_with_timer(2, timer);
+
+		// when / then
+		assert_eq!(t_map.next_expiry(), None);
+
+		t_map.insert(1, 0);
+		assert_eq!(t_map.next_expiry(), Some(2));
+
+		time.set(1);
+		assert_eq!(t_map.next_expiry(), Some(1));
+
+		// refreshing the key pushes its expiry back
+		t_map.insert(1, 0);
+		assert_eq!(t_map.next_expiry(), Some(2));
+
+		t_map.prune();
+		assert_eq!(t_map.next_expiry(), Some(2));
+	}
+
+	#[test]
+	fn should_respect_per_key_lifetime() {
+		// given
+		let time = Cell::new(0);
+		let timer = TestTimer {
+			time: &time
+		};
+		let mut t_map = TransientHashMap::new_with_timer(2, timer);
+
+		// when
+		t_map.insert(1, 0);
+		t_map.insert_with_lifetime(2, 0, 10);
+
+		// then
+		assert_eq!(t_map.remaining_lifetime(&1), Some(2));
+		assert_eq!(t_map.remaining_lifetime(&2), Some(10));
+
+		time.set(3);
+		let removed = t_map.prune();
+		assert_eq!(removed, vec![1]);
+		assert_eq!(t_map.remaining_lifetime(&2), Some(7));
+	}
+
+	#[test]
+	fn should_retain_live_entries_matching_predicate() {
+		// given
+		let time = Cell::new(0);
+		let timer = TestTimer {
+			time: &time
+		};
+		let mut t_map = TransientHashMap::new_with_timer(10, timer);
+		t_map.insert(1, 1);
+		t_map.insert(2, 2);
+		t_map.insert_with_lifetime(3, 3, 1);
+
+		// when
+		time.set(2);
+		t_map.retain(|_, value| value % 2 == 0);
+
+		// then
+		// key 3 is gone because it already expired (lifetime 1, now 2)
+		// key 1 is gone because the predicate rejected it
+		// key 2 survives both checks
+		assert_eq!(t_map.direct().len(), 1);
+		assert!(t_map.contains_key(&2));
+	}
+
+	#[test]
+	fn should_evict_least_recently_used_entry_when_over_capacity() {
+		// given
+		let time = Cell::new(0);
+		let timer = TestTimer {
+			time: &time
+		};
+		let mut t_map = TransientHashMap::with_capacity_and_timer(10, 2, timer);
+
+		// when
+		t_map.insert(1, "one");
+		time.set(1);
+		t_map.insert(2, "two");
+		time.set(2);
+		t_map.get(&1); // refresh 1, making 2 the least recently used
+		t_map.insert(3, "three");
+
+		// then
+		assert_eq!(t_map.take_evicted(), vec![(2, "two")]);
+		assert_eq!(t_map.direct().len(), 2);
+		assert!(t_map.contains_key(&1));
+		assert!(t_map.contains_key(&3));
+		assert_eq!(t_map.capacity(), Some(2));
+	}
+
+	#[test]
+	fn should_prune_past_an_orphaned_heap_entry_left_by_eviction() {
+		// given
+		let time = Cell::new(0);
+		let timer = TestTimer {
+			time: &time
+		};
+		let mut t_map = TransientHashMap::with_capacity_and_timer(1000, 2, timer);
+		t_map.insert_with_lifetime(1, "one", 1);
+		time.set(1);
+		t_map.insert_with_lifetime(2, "two", 1);
+
+		// when: capacity eviction of key 1 (the least recently used) drops its
+		// `timestamps` entry, leaving its heap entry orphaned with nothing left
+		// in `timestamps` to match
+		t_map.insert(3, "three");
+		assert_eq!(t_map.take_evicted(), vec![(1, "one")]);
+
+		time.set(3);
+		let pruned = t_map.prune();
+
+		// then: the orphaned heap entry must not stop the iterator from reaching
+		// key 2, which is genuinely expired
+		assert_eq!(pruned, vec![2]);
+		assert!(!t_map.contains_key(&2));
+	}
+
+	#[test]
+	fn should_evict_least_recently_used_entries_when_over_weight() {
+		// given
+		let time = Cell::new(0);
+		let timer = TestTimer {
+			time: &time
+		};
+		// "one" and "two" both weigh 3, so the budget holds exactly two of them.
+		let mut t_map = TransientHashMap::with_max_weight_and_timer(10, 6, timer);
+
+		// when
+		t_map.insert(1, "one");
+		time.set(1);
+		t_map.insert(2, "two");
+		assert_eq!(t_map.current_weight(), 6);
+
+		// "seven" weighs 5, so both "one" and "two" must go to make room
+		t_map.insert(3, "seven");
+
+		// then
+		let mut evicted = t_map.take_evicted();
+		evicted.sort();
+		assert_eq!(evicted, vec![(1, "one"), (2, "two")]);
+		assert_eq!(t_map.current_weight(), 5);
+		assert_eq!(t_map.max_weight(), Some(6));
+		assert!(t_map.contains_key(&3));
+	}
+
+	#[test]
+	fn should_not_double_count_weight_when_insert_replaces_a_key() {
+		// given
+		let time = Cell::new(0);
+		let timer = TestTimer {
+			time: &time
+		};
+		let mut t_map = TransientHashMap::with_max_weight_and_timer(10, 100, timer);
+
+		// when
+		t_map.insert(1, "one"); // weight 3
+		t_map.insert(1, "seven"); // replaces key 1, weight 5
+
+		// then
+		assert_eq!(t_map.current_weight(), 5);
+	}
+
+	#[test]
+	fn should_not_evict_the_replaced_key_itself_when_over_weight() {
+		// given
+		let time = Cell::new(0);
+		let timer = TestTimer {
+			time: &time
+		};
+		// budget only fits one entry of weight 3; key 1 already holds that slot.
+		let mut t_map = TransientHashMap::with_max_weight_and_timer(10, 3, timer);
+		t_map.insert(1, "one");
+
+		// when: replacing key 1 with a same-weight value must not evict key 1 itself
+		let old = t_map.insert(1, "two");
+
+		// then
+		assert_eq!(old, Some("one"));
+		assert_eq!(t_map.take_evicted(), vec![]);
+		assert_eq!(t_map.current_weight(), 3);
+		assert!(t_map.contains_key(&1));
+	}
+
+	#[test]
+	fn should_drain_expired_entries_with_their_values() {
+		// given
+		let time = Cell::new(0);
+		let timer = TestTimer {
+			time: &time
+		};
+		let mut t_map = TransientHashMap::new_with_timer(2, timer);
+		t_map.insert(1, "one");
+		t_map.insert(2, "two");
+
+		// when
+		time.set(2);
+		let mut drained: Vec<_> = t_map.drain_expired().collect();
+		drained.sort();
+
+		// then
+		assert_eq!(drained, vec![(1, "one"), (2, "two")]);
+		assert_eq!(t_map.direct().len(), 0);
+	}
+
 	#[test]
 	fn it_works() {
 		let time = Cell::new(0);